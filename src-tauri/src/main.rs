@@ -2,14 +2,17 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::process::{Command, Stdio, Child};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread;
 use std::env;
 use std::time::Instant;
 use tauri::{Manager, AppHandle, Emitter, PhysicalPosition};
 use std::io::Read;
 use regex::Regex;
+use futures_util::StreamExt;
 
 // --- UPDATED: Correct imports for the global shortcut plugin ---
 use tauri_plugin_global_shortcut::{GlobalShortcutExt};
@@ -17,43 +20,321 @@ use tauri_plugin_global_shortcut::{GlobalShortcutExt};
 use windows::Win32::Foundation::HWND;
 use windows::Win32::UI::WindowsAndMessaging::{SetWindowDisplayAffinity, WDA_EXCLUDEFROMCAPTURE};
 
+// --- Runtime-configurable shortcut settings ---
+// Persisted as JSON next to api_keys.env so the overlay controls can be
+// remapped without recompiling (global chords collide with other apps a lot).
+const SHORTCUTS_FILE: &str = "shortcuts.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ShortcutConfig {
+    toggle_clickthrough: String,
+    toggle_visibility: String,
+    // Debounce window in milliseconds; widen it to tolerate slight mistiming.
+    debounce_ms: u64,
+}
+impl Default for ShortcutConfig {
+    fn default() -> Self {
+        Self {
+            toggle_clickthrough: "Ctrl+Shift+C".to_string(),
+            toggle_visibility: "Ctrl+\\".to_string(),
+            debounce_ms: 200,
+        }
+    }
+}
+impl ShortcutConfig {
+    fn load() -> Self {
+        std::fs::read_to_string(SHORTCUTS_FILE)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("Failed to serialize shortcuts: {}", e))?;
+        std::fs::write(SHORTCUTS_FILE, json).map_err(|e| format!("Failed to write {}: {}", SHORTCUTS_FILE, e))
+    }
+}
+
+// --- Lock-free shared flags ---
+// These used to live inside `Mutex<AppState>`, but the shortcut handlers held
+// the guard across `set_ignore_cursor_events`/`emit` while the invoke handlers
+// locked the same mutex, which invited re-entrant-lock hangs. Keeping the
+// primitive flags as atomics lets the handlers debounce and toggle wait-free and
+// never hold a lock across a Tauri call. `base` anchors the millisecond clock the
+// per-shortcut timestamps are measured against.
+struct Shared {
+    click_through_enabled: AtomicBool,
+    clickthrough_last_fired: AtomicU64,
+    visibility_last_fired: AtomicU64,
+    base: Instant,
+    // Monotonic source of request ids handed back to the frontend so it can
+    // correlate streamed `gemini_partial`/`gemini_complete` events.
+    gemini_counter: AtomicU64,
+    // Monotonic source of conversation-turn ids. Each turn carries a stable id so
+    // a failing request can remove exactly the turn it pushed, even when a racing
+    // call has since appended a different one.
+    turn_counter: AtomicU64,
+}
+impl Shared {
+    fn new() -> Self {
+        Self {
+            click_through_enabled: AtomicBool::new(true),
+            clickthrough_last_fired: AtomicU64::new(0),
+            visibility_last_fired: AtomicU64::new(0),
+            base: Instant::now(),
+            gemini_counter: AtomicU64::new(0),
+            turn_counter: AtomicU64::new(0),
+        }
+    }
+    fn now_ms(&self) -> u64 {
+        self.base.elapsed().as_millis() as u64
+    }
+}
+
+// Claim a fire slot for a shortcut if `debounce_ms` has elapsed since its last
+// fire. Wait-free: the `compare_exchange` ensures that of two near-simultaneous
+// callbacks only one wins, so OS double-fires are swallowed without a lock.
+fn try_fire(slot: &AtomicU64, now_ms: u64, debounce_ms: u64) -> bool {
+    let last = slot.load(Ordering::Acquire);
+    if now_ms.saturating_sub(last) <= debounce_ms {
+        return false;
+    }
+    slot.compare_exchange(last, now_ms, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+}
+
 // --- Shared State to hold the running process ---
 struct AppState {
     transcription_process: Option<Child>,
-    click_through_enabled: bool,
-    last_shortcut_time: Instant
+    shortcut_config: ShortcutConfig,
+    // Running Gemini transcript, so each prompt carries the earlier turns.
+    conversation: Vec<Content>,
+    // Abort handle for the in-flight streaming request, if any.
+    gemini_task: Option<tauri::async_runtime::JoinHandle<()>>,
+    // Id of the user turn awaiting a reply from the in-flight request. Aborting
+    // the task skips its completion/error paths, so whoever cancels it must drop
+    // this orphaned turn itself.
+    gemini_pending_turn: Option<u64>,
+    // Which window labels are subscribed to which event categories, so the
+    // compact overlay and the scrollback window can opt in/out independently.
+    event_routing: HashMap<String, Vec<String>>,
 }
 impl AppState {
-    fn new() -> Self {
-        Self { 
+    fn new(shortcut_config: ShortcutConfig) -> Self {
+        // Defaults: the overlay sees everything; the history window only
+        // receives finalized segments.
+        let mut event_routing = HashMap::new();
+        event_routing.insert(
+            "main".to_string(),
+            vec![CAT_INTERIM.to_string(), CAT_FINAL.to_string(), CAT_CLICKTHROUGH.to_string(), CAT_GEMINI.to_string()],
+        );
+        event_routing.insert("history".to_string(), vec![CAT_FINAL.to_string()]);
+
+        Self {
             transcription_process: None,
-            click_through_enabled: true,
-            last_shortcut_time: Instant::now()
+            shortcut_config,
+            conversation: Vec::new(),
+            gemini_task: None,
+            gemini_pending_turn: None,
+            event_routing,
         }
     }
 }
 
-// --- Live Transcription using whisper-stream.exe ---
+// --- Event routing ---
+// Categories windows can subscribe to. Events are delivered with `emit_to` so a
+// compact overlay can show only interim captions while a separate scrollback
+// window collects finalized segments.
+const CAT_INTERIM: &str = "transcription_interim";
+const CAT_FINAL: &str = "transcription_final";
+const CAT_CLICKTHROUGH: &str = "clickthrough";
+const CAT_GEMINI: &str = "gemini";
+
+// Emit `event` to every window subscribed to `category`. Only the matching
+// labels are cloned out from under the lock — the interim caption path runs this
+// on every `\r`, so cloning the whole routing map per segment would be wasteful.
+fn emit_to_category<S: Serialize + Clone>(app_handle: &AppHandle, category: &str, event: &str, payload: S) {
+    let labels: Vec<String> = {
+        let state = app_handle.state::<Mutex<AppState>>();
+        let guard = state.lock().unwrap();
+        guard
+            .event_routing
+            .iter()
+            .filter(|(_, categories)| categories.iter().any(|c| c == category))
+            .map(|(label, _)| label.clone())
+            .collect()
+    };
+    for label in &labels {
+        let _ = app_handle.emit_to(label.as_str(), event, payload.clone());
+    }
+}
+
 #[tauri::command]
-fn start_live_transcription(
-    app_handle: AppHandle,
-    stream_exe_path: String,
-    model_path: String,
+fn set_event_routing(window_label: String, categories: Vec<String>, state: tauri::State<Mutex<AppState>>) {
+    state.lock().unwrap().event_routing.insert(window_label, categories);
+}
+
+// Register both overlay chords from the given config, unregistering them
+// first so this is safe to call on hot-reload and at runtime.
+fn register_shortcuts(app: &AppHandle, config: &ShortcutConfig) -> Result<(), String> {
+    if config.toggle_clickthrough == config.toggle_visibility {
+        // The plugin's internal map is keyed by chord string, so registering the
+        // same chord twice would silently overwrite the first handler instead of
+        // erroring — click-through would stop firing with nothing to show for it.
+        return Err("toggle_clickthrough and toggle_visibility cannot be the same chord".to_string());
+    }
+
+    let shortcuts = app.global_shortcut();
+
+    // click-through toggle
+    let clickthrough_chord = config.toggle_clickthrough.clone();
+    let _ = shortcuts.unregister(clickthrough_chord.as_str());
+    shortcuts
+        .on_shortcut(clickthrough_chord.as_str(), move |app, _shortcut, _event| {
+            let window = match app.get_webview_window("main") {
+                Some(w) => w,
+                None => return,
+            };
+            let shared = app.state::<Shared>();
+            let debounce = app.state::<Mutex<AppState>>().lock().unwrap().shortcut_config.debounce_ms;
+
+            if try_fire(&shared.clickthrough_last_fired, shared.now_ms(), debounce) {
+                // fetch_xor returns the previous value; the new state is its negation.
+                let is_enabled = !shared.click_through_enabled.fetch_xor(true, Ordering::AcqRel);
+                let _ = window.set_ignore_cursor_events(is_enabled);
+                emit_to_category(app, CAT_CLICKTHROUGH, "click_through_toggled", is_enabled);
+            }
+        })
+        .map_err(|e| format!("Failed to set click-through shortcut: {}", e))?;
+
+    // hide/show toggle
+    let visibility_chord = config.toggle_visibility.clone();
+    let _ = shortcuts.unregister(visibility_chord.as_str());
+    shortcuts
+        .on_shortcut(visibility_chord.as_str(), move |app, _shortcut, _event| {
+            if let Some(window) = app.get_webview_window("main") {
+                let shared = app.state::<Shared>();
+                let debounce = app.state::<Mutex<AppState>>().lock().unwrap().shortcut_config.debounce_ms;
+
+                if try_fire(&shared.visibility_last_fired, shared.now_ms(), debounce) {
+                    if window.is_visible().unwrap_or(false) {
+                        let _ = window.hide();
+                    } else {
+                        let _ = window.show();
+                        let _ = window.set_focus();
+                    }
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to set visibility shortcut: {}", e))?;
+
+    Ok(())
+}
+
+#[tauri::command]
+fn get_shortcuts(state: tauri::State<Mutex<AppState>>) -> ShortcutConfig {
+    state.lock().unwrap().shortcut_config.clone()
+}
+
+#[tauri::command]
+fn set_shortcuts(
+    app: AppHandle,
+    config: ShortcutConfig,
     state: tauri::State<Mutex<AppState>>,
+    shared: tauri::State<Shared>,
 ) -> Result<(), String> {
-    let mut state_guard = state.lock().unwrap();
-    let mut command = Command::new(&stream_exe_path);
-    command.args(["-m", &model_path, "-t", "8"]);
-    command.stdout(Stdio::piped());
-    let mut child = command.spawn().map_err(|e| format!("Failed to spawn process: {}", e))?;
-    let mut stdout = child.stdout.take().expect("Failed to capture stdout");
+    let old_config = state.lock().unwrap().shortcut_config.clone();
 
-    // Precompile ANSI escape regex (e.g. \x1b[...m or \x1b[2K)
-    let ansi_re = Regex::new(r"\x1B\[[0-?]*[ -/]*[@-~]").unwrap();
+    // Unregister the currently-bound chords before rebinding.
+    {
+        let shortcuts = app.global_shortcut();
+        let _ = shortcuts.unregister(old_config.toggle_clickthrough.as_str());
+        let _ = shortcuts.unregister(old_config.toggle_visibility.as_str());
+    }
+
+    if let Err(e) = register_shortcuts(&app, &config) {
+        // If the click-through chord registered but the visibility chord then
+        // failed (or vice versa), the runtime would be left with one old chord
+        // gone and one new chord live — out of sync with both the persisted
+        // config and what `get_shortcuts` reports. Roll back to the old chords
+        // so a partial failure can't leave that gap; `register_shortcuts`
+        // unregisters before registering, so this cleanly restores them
+        // regardless of which chord the failed attempt left bound. The new chords
+        // are unregistered first so none of them stays live after the rollback.
+        {
+            let shortcuts = app.global_shortcut();
+            let _ = shortcuts.unregister(config.toggle_clickthrough.as_str());
+            let _ = shortcuts.unregister(config.toggle_visibility.as_str());
+        }
+        let _ = register_shortcuts(&app, &old_config);
+        return Err(e);
+    }
+
+    // Update the in-memory config to match what the runtime is now bound to
+    // *before* persisting. If `save()` fails we still return the error, but
+    // `shortcut_config` and the registered chords stay in sync — otherwise
+    // `get_shortcuts` would report stale values and the next `set_shortcuts`
+    // would unregister the wrong chords and leak the bound ones.
+    state.lock().unwrap().shortcut_config = config.clone();
+    shared.clickthrough_last_fired.store(0, Ordering::Release);
+    shared.visibility_last_fired.store(0, Ordering::Release);
+
+    config.save()?;
+    Ok(())
+}
+
+// --- Live Transcription ---
+// One JSON object per segment, as emitted by JSON-lines capable engines. The
+// raw-text backend fills in best-effort values so the frontend sees the same
+// shape regardless of backend.
+#[derive(Clone, Serialize, Deserialize)]
+struct TranscriptSegment {
+    text: String,
+    #[serde(default)] t0: f64,
+    #[serde(default)] t1: f64,
+    #[serde(default = "default_confidence")] confidence: f32,
+    #[serde(default)] is_final: bool,
+}
+fn default_confidence() -> f32 { 1.0 }
+impl TranscriptSegment {
+    // Best-effort segment for backends that only produce plain text: no
+    // timestamps, full confidence. Keeps the event payload shape uniform so a
+    // single frontend listener can read `.text`/`.confidence` regardless of backend.
+    fn text_only(text: String, is_final: bool) -> Self {
+        Self { text, t0: 0.0, t1: 0.0, confidence: default_confidence(), is_final }
+    }
+}
+
+// A transcription engine plus its output-parsing strategy. Implementors own
+// both how the child process is launched and how its stdout is turned into
+// `new_transcription`/`final_transcription` events.
+trait TranscriptionBackend: Send {
+    fn spawn(&self, stream_exe_path: &str, model_path: &str) -> Result<Child, String>;
+    fn read_loop(&self, stdout: std::process::ChildStdout, app_handle: AppHandle);
+    // Identifies which strategy `select_backend` picked; exists so that choice
+    // can be asserted in tests without spawning a real child process.
+    fn name(&self) -> &'static str;
+}
+
+// Default backend: whisper-stream.exe printing carriage-return-updated lines
+// with ANSI escapes. Wraps each cleaned line in a `TranscriptSegment` so the
+// payload shape matches the JSON-lines backend.
+struct RawTextBackend;
+impl TranscriptionBackend for RawTextBackend {
+    fn spawn(&self, stream_exe_path: &str, model_path: &str) -> Result<Child, String> {
+        let mut command = Command::new(stream_exe_path);
+        command.args(["-m", model_path, "-t", "8"]);
+        command.stdout(Stdio::piped());
+        command.spawn().map_err(|e| format!("Failed to spawn process: {}", e))
+    }
+
+    fn name(&self) -> &'static str {
+        "raw-text"
+    }
+
+    fn read_loop(&self, mut stdout: std::process::ChildStdout, app_handle: AppHandle) {
+        // Precompile ANSI escape regex (e.g. \x1b[...m or \x1b[2K)
+        let ansi_re = Regex::new(r"\x1B\[[0-?]*[ -/]*[@-~]").unwrap();
 
-    // Spawn thread to read raw bytes from stdout and detect \r vs \n
-    let app_handle_clone = app_handle.clone();
-    thread::spawn(move || {
         let mut buffer = [0u8; 1024];
         let mut acc: Vec<u8> = Vec::new();
 
@@ -65,7 +346,7 @@ fn start_live_transcription(
                         if let Ok(s) = String::from_utf8(acc.clone()) {
                             let cleaned = ansi_re.replace_all(&s, "").to_string().trim().to_string();
                             if !cleaned.is_empty() {
-                                let _ = app_handle_clone.emit("final_transcription", cleaned);
+                                emit_to_category(&app_handle, CAT_FINAL, "final_transcription", TranscriptSegment::text_only(cleaned, true));
                             }
                         }
                     }
@@ -80,7 +361,7 @@ fn start_live_transcription(
                                     if let Ok(s) = String::from_utf8(acc.clone()) {
                                         let cleaned = ansi_re.replace_all(&s, "").to_string().trim().to_string();
                                         if !cleaned.is_empty() {
-                                            let _ = app_handle_clone.emit("new_transcription", cleaned.clone());
+                                            emit_to_category(&app_handle, CAT_INTERIM, "new_transcription", TranscriptSegment::text_only(cleaned, false));
                                         }
                                     }
                                 } else {
@@ -95,7 +376,7 @@ fn start_live_transcription(
                                     if let Ok(s) = String::from_utf8(acc.clone()) {
                                         let cleaned = ansi_re.replace_all(&s, "").to_string().trim().to_string();
                                         if !cleaned.is_empty() {
-                                            let _ = app_handle_clone.emit("final_transcription", cleaned.clone());
+                                            emit_to_category(&app_handle, CAT_FINAL, "final_transcription", TranscriptSegment::text_only(cleaned, true));
                                         }
                                     }
                                     acc.clear();
@@ -115,6 +396,84 @@ fn start_live_transcription(
                 }
             }
         }
+    }
+}
+
+// Backend for engines that print one JSON object per segment. Each line is
+// deserialized into a `TranscriptSegment` and emitted as the event payload, so
+// the frontend gets timestamps and confidence for highlighting low-confidence words.
+struct JsonLinesBackend;
+impl TranscriptionBackend for JsonLinesBackend {
+    fn spawn(&self, stream_exe_path: &str, model_path: &str) -> Result<Child, String> {
+        let mut command = Command::new(stream_exe_path);
+        // `--json` asks the engine for line-delimited JSON segments.
+        command.args(["-m", model_path, "-t", "8", "--json"]);
+        command.stdout(Stdio::piped());
+        command.spawn().map_err(|e| format!("Failed to spawn process: {}", e))
+    }
+
+    fn name(&self) -> &'static str {
+        "json-lines"
+    }
+
+    fn read_loop(&self, stdout: std::process::ChildStdout, app_handle: AppHandle) {
+        use std::io::{BufRead, BufReader};
+        let reader = BufReader::new(stdout);
+        for line in reader.lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(err) => {
+                    eprintln!("Error reading stdout: {}", err);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<TranscriptSegment>(&line) {
+                Ok(segment) => {
+                    let (category, event) = if segment.is_final {
+                        (CAT_FINAL, "final_transcription")
+                    } else {
+                        (CAT_INTERIM, "new_transcription")
+                    };
+                    emit_to_category(&app_handle, category, event, segment);
+                }
+                Err(err) => eprintln!("Failed to parse transcript segment: {}", err),
+            }
+        }
+    }
+}
+
+fn select_backend(backend: Option<&str>) -> Box<dyn TranscriptionBackend> {
+    match backend {
+        Some("json") | Some("json-lines") => Box::new(JsonLinesBackend),
+        // Plain-text default, preserving the original behavior when the frontend
+        // omits `backend` entirely.
+        _ => Box::new(RawTextBackend),
+    }
+}
+
+#[tauri::command]
+fn start_live_transcription(
+    app_handle: AppHandle,
+    stream_exe_path: String,
+    model_path: String,
+    // Optional: omitting it selects the raw-text backend, so existing
+    // `invoke("start_live_transcription", { streamExePath, modelPath })` calls
+    // keep working unchanged.
+    #[serde(default)] backend: Option<String>,
+    state: tauri::State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let mut state_guard = state.lock().unwrap();
+    let strategy = select_backend(backend.as_deref());
+    let mut child = strategy.spawn(&stream_exe_path, &model_path)?;
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+
+    // Spawn a reader thread driven by the selected backend's parsing strategy.
+    let app_handle_clone = app_handle.clone();
+    thread::spawn(move || {
+        strategy.read_loop(stdout, app_handle_clone);
     });
 
     state_guard.transcription_process = Some(child);
@@ -132,34 +491,221 @@ fn stop_live_transcription(state: tauri::State<Mutex<AppState>>) -> Result<(), S
 }
 
 // --- Gemini API Logic ---
-#[derive(Serialize)] struct GeminiRequest { contents: Vec<Content> }
-#[derive(Serialize)] struct Content { parts: Vec<Part> }
-#[derive(Serialize)] struct Part { text: String }
+// `role` ("user"/"model") is what the Gemini REST API uses to thread a
+// multi-turn conversation; the optional top-level `system_instruction` seeds
+// the assistant's persona and is loaded from config.
+#[derive(Serialize)] struct GeminiRequest {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemContent>,
+}
+// `id` is local bookkeeping for removing a turn by identity; it is never sent to
+// Gemini (skipped during serialization).
+#[derive(Serialize, Clone)] struct Content { #[serde(skip)] id: u64, role: String, parts: Vec<Part> }
+#[derive(Serialize, Clone)] struct Part { text: String }
+#[derive(Serialize)] struct SystemContent { parts: Vec<Part> }
 #[derive(Deserialize)] struct GeminiResponse { candidates: Vec<Candidate> }
 #[derive(Deserialize)] struct Candidate { content: ContentResponse }
 #[derive(Deserialize)] struct ContentResponse { parts: Vec<PartResponse> }
 #[derive(Deserialize)] struct PartResponse { text: String }
 
+// Optional system instruction, loaded from api_keys.env alongside the API key.
+fn system_instruction() -> Option<String> {
+    env::var("GEMINI_SYSTEM_INSTRUCTION").ok().filter(|s| !s.trim().is_empty())
+}
+
+// Drain complete `\n`-terminated lines out of `buf`, decoding each with lossy
+// UTF-8 only once it's whole. Pulled out of the SSE read loop so the
+// chunk-boundary behavior (a multi-byte character split across two network
+// chunks must not turn into U+FFFD) can be exercised without a real stream.
+fn drain_lines(buf: &mut Vec<u8>) -> Vec<String> {
+    let mut lines = Vec::new();
+    while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+        let line_bytes: Vec<u8> = buf.drain(..=pos).collect();
+        lines.push(String::from_utf8_lossy(&line_bytes).trim().to_string());
+    }
+    lines
+}
+
+// Payload for the streamed Gemini events; `id` lets the frontend correlate a
+// stream of `gemini_partial` updates with the `gemini_complete` that ends it —
+// mirroring the interim/final split of `new_transcription`/`final_transcription`.
+#[derive(Clone, Serialize)] struct GeminiStreamEvent { id: String, text: String }
+
+// Drop a user turn whose request failed, and clear the pending marker if it
+// still points at that turn, so the next call doesn't send two user roles.
+fn clear_pending_turn(app_handle: &AppHandle, turn_id: u64) {
+    let state = app_handle.state::<Mutex<AppState>>();
+    let mut guard = state.lock().unwrap();
+    guard.conversation.retain(|c| c.id != turn_id);
+    if guard.gemini_pending_turn == Some(turn_id) {
+        guard.gemini_pending_turn = None;
+        guard.gemini_task = None;
+    }
+}
+
+#[tauri::command]
+fn reset_conversation(state: tauri::State<Mutex<AppState>>) {
+    let mut state_guard = state.lock().unwrap();
+    // Abort any in-flight request first, the same way `cancel_gemini_api` does:
+    // otherwise it would complete after the clear below and push a `model` turn
+    // onto a freshly-cleared (or already-resumed) conversation with no preceding
+    // `user` turn, breaking Gemini's strict role alternation.
+    if let Some(handle) = state_guard.gemini_task.take() {
+        handle.abort();
+    }
+    state_guard.gemini_pending_turn = None;
+    state_guard.conversation.clear();
+}
+
+#[tauri::command]
+fn cancel_gemini_api(state: tauri::State<Mutex<AppState>>) {
+    let mut state_guard = state.lock().unwrap();
+    if let Some(handle) = state_guard.gemini_task.take() {
+        handle.abort();
+    }
+    // The aborted task never appends its model reply, so remove the orphaned
+    // user turn it left behind; otherwise the next call would send two user roles.
+    if let Some(pending) = state_guard.gemini_pending_turn.take() {
+        state_guard.conversation.retain(|c| c.id != pending);
+    }
+}
+
 #[tauri::command]
-async fn call_gemini_api(prompt: String) -> Result<String, String> {
+fn call_gemini_api(
+    app_handle: AppHandle,
+    prompt: String,
+    state: tauri::State<Mutex<AppState>>,
+    shared: tauri::State<Shared>,
+) -> Result<String, String> {
     let api_key = env::var("GEMINI_API_KEY").map_err(|_| "GEMINI_API_KEY not found in .env file".to_string())?;
-        let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:generateContent?key={}",
+    let url = format!(
+        "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse&key={}",
         api_key
     );
-    let request_body = GeminiRequest { contents: vec![Content { parts: vec![Part { text: prompt }] }] };
-    let client = reqwest::Client::new();
-    let response = client.post(&url).json(&request_body).send().await.map_err(|e| format!("Failed to send request to Gemini API: {}", e))?;
-    if response.status().is_success() {
-        let gemini_response = response.json::<GeminiResponse>().await.map_err(|e| format!("Failed to parse Gemini response: {}", e))?;
-        if let Some(candidate) = gemini_response.candidates.get(0) {
-            if let Some(part) = candidate.content.parts.get(0) { return Ok(part.text.clone()); }
+
+    let request_id = format!("req-{}", shared.gemini_counter.fetch_add(1, Ordering::Relaxed));
+
+    // Tag the user turn with a stable id so the async task can pop exactly this
+    // turn on failure — not whichever turn happens to be last by the time it errors.
+    let turn_id = shared.turn_counter.fetch_add(1, Ordering::Relaxed);
+
+    // Append the user turn and snapshot the full history to send.
+    let contents = {
+        let mut state_guard = state.lock().unwrap();
+        // Cancel any request already in flight and drop its orphaned user turn
+        // first, so killing it mid-stream can't leave a trailing user role that
+        // collides with the one we are about to push.
+        if let Some(handle) = state_guard.gemini_task.take() {
+            handle.abort();
+        }
+        if let Some(pending) = state_guard.gemini_pending_turn.take() {
+            state_guard.conversation.retain(|c| c.id != pending);
+        }
+        // Belt-and-suspenders: never send Gemini two `user` roles in a row.
+        if state_guard.conversation.last().map(|c| c.role == "user").unwrap_or(false) {
+            state_guard.conversation.pop();
+        }
+        state_guard.conversation.push(Content { id: turn_id, role: "user".to_string(), parts: vec![Part { text: prompt }] });
+        // Mark this turn pending while still under the same lock that pushed it.
+        // Setting it after spawn could clobber the task's own `pending = None` if
+        // the task finishes first, resurrecting an already-answered turn.
+        state_guard.gemini_pending_turn = Some(turn_id);
+        state_guard.conversation.clone()
+    };
+    let request_body = GeminiRequest {
+        contents,
+        system_instruction: system_instruction().map(|text| SystemContent { parts: vec![Part { text }] }),
+    };
+
+    let id = request_id.clone();
+    let handle = tauri::async_runtime::spawn(async move {
+        let client = reqwest::Client::new();
+        let response = match client.post(&url).json(&request_body).send().await {
+            Ok(r) => r,
+            Err(e) => {
+                clear_pending_turn(&app_handle, turn_id);
+                emit_to_category(&app_handle, CAT_GEMINI, "gemini_error", format!("Failed to send request to Gemini API: {}", e));
+                return;
+            }
+        };
+        if !response.status().is_success() {
+            let error_body = response.text().await.unwrap_or_else(|_| "Unknown API error".to_string());
+            clear_pending_turn(&app_handle, turn_id);
+            emit_to_category(&app_handle, CAT_GEMINI, "gemini_error", format!("Gemini API error: {}", error_body));
+            return;
+        }
+
+        // Consume the SSE byte stream, parsing one `data:` line at a time and
+        // emitting the accumulated text as it grows. `bytes_stream` chunk
+        // boundaries aren't guaranteed to land on UTF-8 character boundaries, so
+        // raw bytes are accumulated here and only decoded once a full `\n`-terminated
+        // line is available — decoding each chunk independently would mangle any
+        // multi-byte character (non-English text, emoji, smart quotes) split across two chunks.
+        let mut stream = response.bytes_stream();
+        let mut buf: Vec<u8> = Vec::new();
+        let mut acc = String::new();
+        while let Some(chunk) = stream.next().await {
+            let bytes = match chunk {
+                Ok(b) => b,
+                Err(_) => break,
+            };
+            buf.extend_from_slice(&bytes);
+            for line in drain_lines(&mut buf) {
+                let line = line.as_str();
+                if let Some(data) = line.strip_prefix("data:") {
+                    let data = data.trim();
+                    if data.is_empty() || data == "[DONE]" {
+                        continue;
+                    }
+                    if let Ok(resp) = serde_json::from_str::<GeminiResponse>(data) {
+                        if let Some(candidate) = resp.candidates.get(0) {
+                            if let Some(part) = candidate.content.parts.get(0) {
+                                acc.push_str(&part.text);
+                                emit_to_category(&app_handle, CAT_GEMINI, "gemini_partial", GeminiStreamEvent { id: id.clone(), text: acc.clone() });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Record the model's reply so the next turn sees it, then signal the end.
+        let model_id = app_handle.state::<Shared>().turn_counter.fetch_add(1, Ordering::Relaxed);
+        {
+            let state = app_handle.state::<Mutex<AppState>>();
+            let mut guard = state.lock().unwrap();
+            // Only push if this turn is still the tracked pending one. There is a
+            // narrow gap between this task being spawned and its JoinHandle being
+            // stored in `gemini_task` below; if `cancel_gemini_api`/`reset_conversation`
+            // runs in that gap it finds no handle to `.abort()` but still clears
+            // `gemini_pending_turn` and drops the user turn. Without this check the
+            // task would keep running unreferenced and push a `model` turn with no
+            // preceding `user` turn once it completes, breaking Gemini's strict role
+            // alternation for every call after.
+            if guard.gemini_pending_turn == Some(turn_id) {
+                guard.conversation.push(Content { id: model_id, role: "model".to_string(), parts: vec![Part { text: acc.clone() }] });
+                // This turn is now answered; stop tracking it as orphanable and drop
+                // our own finished JoinHandle so it isn't left dangling in AppState.
+                guard.gemini_pending_turn = None;
+                guard.gemini_task = None;
+            } else {
+                return;
+            }
+        }
+        emit_to_category(&app_handle, CAT_GEMINI, "gemini_complete", GeminiStreamEvent { id, text: acc });
+    });
+
+    // Store the abort handle. `gemini_pending_turn` was already set under the
+    // push lock above; only replace the handle if this turn is still the pending
+    // one, so we don't resurrect a handle after a fast task already cleared it.
+    {
+        let mut state_guard = state.lock().unwrap();
+        if state_guard.gemini_pending_turn == Some(turn_id) {
+            state_guard.gemini_task = Some(handle);
         }
-        Err("No content found in Gemini response".to_string())
-    } else {
-        let error_body = response.text().await.unwrap_or_else(|_| "Unknown API error".to_string());
-        Err(format!("Gemini API error: {}", error_body))
     }
+    Ok(request_id)
 }
 
 // --- Window Invisibility Logic ---
@@ -172,26 +718,28 @@ fn make_window_invisible_to_capture(window: &tauri::WebviewWindow) {
 fn toggle_clickthrough(
     window: tauri::WebviewWindow,
     enable: bool,
-    state: tauri::State<Mutex<AppState>>
+    shared: tauri::State<Shared>
 ) -> Result<(), String> {
     // Update window behavior
     window.set_ignore_cursor_events(enable).map_err(|e| format!("Failed to set clickthrough: {}", e))?;
 
-    // Update shared state
-    let mut state_guard = state.lock().unwrap();
-    state_guard.click_through_enabled = enable;
+    // Update shared state (lock-free)
+    shared.click_through_enabled.store(enable, Ordering::Release);
 
-    // Emit event so frontend stays in sync
-    let _ = window.emit("click_through_toggled", enable);
+    // Emit event so subscribed windows stay in sync
+    emit_to_category(window.app_handle(), CAT_CLICKTHROUGH, "click_through_toggled", enable);
 
     Ok(())
 }
 
 fn main() {
     dotenvy::from_filename("api_keys.env").expect("Failed to load api_keys.env file");
-    
+
+    let shortcut_config = ShortcutConfig::load();
+
     tauri::Builder::default()
-        .manage(Mutex::new(AppState::new()))
+        .manage(Mutex::new(AppState::new(shortcut_config)))
+        .manage(Shared::new())
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .setup(|app| {
             let main_window = app.get_webview_window("main").unwrap();
@@ -207,7 +755,7 @@ fn main() {
             // get monitor size
             if let Some(monitor) = main_window.current_monitor().unwrap() {
                 let size = monitor.size();
-                
+
                 // place window at top-center
                 let window_size = main_window.outer_size().unwrap();
                 let x = (size.width / 2) as i32 - (window_size.width as i32 / 2);
@@ -216,67 +764,109 @@ fn main() {
                 main_window.set_position(PhysicalPosition::new(x, y)).unwrap();
             }
 
-            // Get the shortcut manager
-            let shortcuts = app.global_shortcut();
-            let _app_handle = app.handle().clone();
-
-            // 1. Unregister to prevent hot-reload errors
-            let _ = shortcuts.unregister("Ctrl+Shift+C");
-
-            // 2. Register the shortcut and provide the handler as a second argument
-            shortcuts.on_shortcut("Ctrl+Shift+C", move |app,_shortcut,_event| {
-                let window = app.get_webview_window("main").unwrap();
-                let state = app.state::<Mutex<AppState>>();
-                
-                let mut state_guard = state.lock().unwrap();
-                let now = Instant::now();
-                
-                if now.duration_since(state_guard.last_shortcut_time).as_millis() > 200 {
-                    // Toggle the boolean state
-                    state_guard.click_through_enabled = !state_guard.click_through_enabled;
-                    let is_enabled = state_guard.click_through_enabled;
-                    
-                    // Apply the new state to the window
-                    let _ = window.set_ignore_cursor_events(is_enabled);
-                    
-                    // Emit the new state to the frontend
-                    let _ = window.emit("click_through_toggled", is_enabled);
-                    
-                    // Update the timestamp
-                    state_guard.last_shortcut_time = now;
-                }
-            }).expect("Failed to set shortcut handler");  
-                     
-            // hide/show toggle
-            let _ = shortcuts.unregister("Ctrl+\\");
-            shortcuts.on_shortcut("Ctrl+\\", move |app, _shortcut, _event| {
-                if let Some(window) = app.get_webview_window("main") {
-                    
-                    let state = app.state::<Mutex<AppState>>();
-
-                    let now = Instant::now();
-                    let mut state_guard = state.lock().unwrap();
-
-                    if now.duration_since(state_guard.last_shortcut_time).as_millis() > 200 {
-                        if window.is_visible().unwrap_or(false) {
-                            let _ = window.hide();
-                        } 
-                        else {
-                            let _ = window.show();
-                            let _ = window.set_focus();
-                        }
-                        state_guard.last_shortcut_time = now;
-                    }
-                }}).expect("Failed to set visibility toggle shortcut");
-            
+            // Dedicated scrollback window for finalized transcript segments; it
+            // only receives the `transcription_final` category by default. It
+            // loads with `?pane=history` so the frontend renders scrollback only
+            // and skips the overlay's startup side effects (e.g. auto-starting
+            // transcription) — otherwise the hidden window would spawn a second
+            // whisper-stream process.
+            let _ = tauri::WebviewWindowBuilder::new(app, "history", tauri::WebviewUrl::App("index.html?pane=history".into()))
+                .title("Transcript History")
+                .visible(false)
+                .build();
+
+            // Register the overlay chords from the persisted config. Global chords
+            // frequently collide with other apps, so a persisted chord failing to
+            // register here is an expected outcome, not a reason to take the whole
+            // app down — fall back to the defaults and re-persist them instead of
+            // `.expect()`-panicking.
+            let app_handle = app.handle().clone();
+            let config = app.state::<Mutex<AppState>>().lock().unwrap().shortcut_config.clone();
+            if let Err(e) = register_shortcuts(&app_handle, &config) {
+                eprintln!("Failed to register persisted shortcuts ({}), falling back to defaults", e);
+                let default_config = ShortcutConfig::default();
+                register_shortcuts(&app_handle, &default_config).expect("Failed to register default shortcuts");
+                app.state::<Mutex<AppState>>().lock().unwrap().shortcut_config = default_config.clone();
+                let _ = default_config.save();
+            }
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             start_live_transcription,
             stop_live_transcription,
             call_gemini_api,
-            toggle_clickthrough
+            cancel_gemini_api,
+            reset_conversation,
+            toggle_clickthrough,
+            get_shortcuts,
+            set_shortcuts,
+            set_event_routing
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_fire_rejects_within_debounce_window() {
+        let slot = AtomicU64::new(0);
+        assert!(try_fire(&slot, 1_000, 200));
+        // Re-fires inside the debounce window are swallowed...
+        assert!(!try_fire(&slot, 1_050, 200));
+        // ...but once it elapses the next fire wins.
+        assert!(try_fire(&slot, 1_250, 200));
+    }
+
+    #[test]
+    fn try_fire_swallows_double_fire_at_same_instant() {
+        let slot = AtomicU64::new(0);
+        assert!(try_fire(&slot, 500, 200));
+        // Simulates an OS double-fire: a second call at the same `now_ms` falls
+        // inside the debounce window of the first and is rejected.
+        assert!(!try_fire(&slot, 500, 200));
+    }
+
+    #[test]
+    fn select_backend_defaults_to_raw_text() {
+        assert_eq!(select_backend(None).name(), "raw-text");
+        assert_eq!(select_backend(Some("whatever")).name(), "raw-text");
+    }
+
+    #[test]
+    fn select_backend_picks_json_lines() {
+        assert_eq!(select_backend(Some("json")).name(), "json-lines");
+        assert_eq!(select_backend(Some("json-lines")).name(), "json-lines");
+    }
+
+    #[test]
+    fn drain_lines_waits_for_a_complete_line() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"data: partial");
+        // No newline yet, so nothing should be drained.
+        assert!(drain_lines(&mut buf).is_empty());
+
+        buf.extend_from_slice(b" line\n");
+        assert_eq!(drain_lines(&mut buf), vec!["data: partial line".to_string()]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn drain_lines_decodes_multibyte_chars_split_across_chunks() {
+        // "café" (é is two bytes in UTF-8): split the accumulator mid-character,
+        // as a `bytes_stream` chunk boundary is allowed to do.
+        let full = "data: café\n".as_bytes().to_vec();
+        let split_at = full.iter().position(|&b| b == 0xC3).unwrap() + 1;
+
+        let mut buf = full[..split_at].to_vec();
+        // The line isn't complete yet, so nothing should decode (and definitely
+        // not a lossily-mangled half-character).
+        assert!(drain_lines(&mut buf).is_empty());
+
+        buf.extend_from_slice(&full[split_at..]);
+        assert_eq!(drain_lines(&mut buf), vec!["data: café".to_string()]);
+    }
+}